@@ -1,5 +1,14 @@
 use core::fmt;
-use std::{collections::BTreeSet, io::Cursor, net::ToSocketAddrs, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    io::Cursor,
+    net::ToSocketAddrs,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 static PUSH_COTS: Lazy<BTreeSet<COT>> =
     Lazy::new(|| BTreeSet::from_iter([COT::Cyclic, COT::Background, COT::Spontan, COT::Init]));
@@ -9,6 +18,7 @@ use iec60870_5::{
     types::COT,
 };
 use once_cell::sync::Lazy;
+use rand::Rng;
 use roboplc::{comm::ConnectionHandler, locking::Mutex};
 use roboplc::{
     comm::{CommReader, Stream, Timeouts},
@@ -18,24 +28,280 @@ use roboplc::{
 use rtsc::{cell::DataCell, time::interval};
 use tracing::{debug, error, trace, warn};
 
+/// IEC 60870-5-104 flow-control and supervisory timer parameters (section 5, companion
+/// standard 104).
+#[derive(Copy, Clone, Debug)]
+pub struct Iec104Params {
+    /// Maximum number of unacknowledged I-format APDUs the client may have outstanding
+    /// before it must block further I-format sends (`k`, default 12).
+    pub k: u16,
+    /// Number of received I-format APDUs after which an S-frame acknowledgement must be
+    /// sent even if there is nothing else to transmit (`w`, default 8, must be `<= 2/3 * k`).
+    pub w: u16,
+    /// Time to wait for an acknowledgement of the oldest outstanding I- or TESTFR frame
+    /// before the connection is considered dead (`t1`, default 15s).
+    pub t1: Duration,
+    /// Time after receiving an I-frame before a spontaneous S-frame acknowledgement must be
+    /// sent if nothing else has been sent in the meantime (`t2`, default 10s, must be `< t1`).
+    pub t2: Duration,
+    /// Idle time (no frame sent or received) after which a TESTFR act is sent to keep the
+    /// link alive (`t3`, default 20s).
+    pub t3: Duration,
+}
+
+impl Default for Iec104Params {
+    fn default() -> Self {
+        Self {
+            k: 12,
+            w: 8,
+            t1: Duration::from_secs(15),
+            t2: Duration::from_secs(10),
+            t3: Duration::from_secs(20),
+        }
+    }
+}
+
+impl Iec104Params {
+    fn validate(&self) -> Result<()> {
+        if self.t2 >= self.t1 {
+            return Err(roboplc::Error::invalid_data(
+                "IEC 104 params: t2 must be less than t1",
+            ));
+        }
+        if u32::from(self.w) * 3 > u32::from(self.k) * 2 {
+            return Err(roboplc::Error::invalid_data(
+                "IEC 104 params: w must not exceed 2/3 of k",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reconnect backoff strategy applied whenever the IEC 104 session needs to reconnect.
+#[derive(Clone, Debug)]
+pub enum ReconnectStrategy {
+    /// Always retry after the same fixed delay.
+    FixedInterval(Duration),
+    /// Multiply the delay by `factor` after every failed attempt, capped at `max_delay`, and
+    /// optionally give up after `max_retries` consecutive failures. The delay is reset to
+    /// `initial` as soon as a connection succeeds.
+    ExponentialBackoff {
+        initial: Duration,
+        factor: f64,
+        max_delay: Duration,
+        max_retries: Option<usize>,
+    },
+    /// Do not throttle reconnects at all.
+    None,
+}
+
+impl Default for ReconnectStrategy {
+    fn default() -> Self {
+        ReconnectStrategy::None
+    }
+}
+
+impl ReconnectStrategy {
+    fn initial_delay(&self) -> Duration {
+        match self {
+            ReconnectStrategy::FixedInterval(delay) => *delay,
+            ReconnectStrategy::ExponentialBackoff { initial, .. } => *initial,
+            ReconnectStrategy::None => Duration::ZERO,
+        }
+    }
+}
+
+/// Tracks the current backoff delay and attempt count for a `ReconnectStrategy`.
+///
+/// `wait_before_retry` is called independently from the `PingKind::Connect` pinger, the t1
+/// path of `supervise()` and `Reader::run`, all of which can notice the very same dropped
+/// connection around the same time. `retry_lock` makes only one of them actually own the
+/// backoff for a given disconnect episode: whichever caller gets there first charges the
+/// attempt and sleeps, the rest see it held and fall straight through.
+struct ReconnectHandler {
+    strategy: ReconnectStrategy,
+    state: Mutex<ReconnectState>,
+    retry_lock: Mutex<()>,
+}
+
+struct ReconnectState {
+    delay: Duration,
+    attempt: usize,
+}
+
+impl ReconnectHandler {
+    fn new(strategy: ReconnectStrategy) -> Self {
+        let delay = strategy.initial_delay();
+        Self {
+            strategy,
+            state: Mutex::new(ReconnectState { delay, attempt: 0 }),
+            retry_lock: Mutex::new(()),
+        }
+    }
+
+    /// Resets the backoff delay, called as soon as a connection attempt succeeds.
+    fn on_connected(&self) {
+        let mut state = self.state.lock();
+        state.attempt = 0;
+        state.delay = self.strategy.initial_delay();
+    }
+
+    /// Blocks for the current backoff delay (plus a little jitter) before the next reconnect
+    /// attempt is allowed to proceed. Returns an error once `max_retries` is exhausted.
+    ///
+    /// If another caller is already backing off for the same disconnect episode, returns
+    /// immediately without charging a second attempt against `max_retries` or the backoff
+    /// curve.
+    fn wait_before_retry(&self) -> Result<()> {
+        let Some(_retry_lock) = self.retry_lock.try_lock() else {
+            return Ok(());
+        };
+        let delay = {
+            let mut state = self.state.lock();
+            state.attempt += 1;
+            if let ReconnectStrategy::ExponentialBackoff {
+                max_retries: Some(max),
+                ..
+            } = &self.strategy
+            {
+                if state.attempt > *max {
+                    return Err(roboplc::Error::io(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "IEC 60870-5 104 reconnect attempts exhausted",
+                    )));
+                }
+            }
+            let delay = state.delay;
+            if let ReconnectStrategy::ExponentialBackoff {
+                factor, max_delay, ..
+            } = &self.strategy
+            {
+                state.delay = state.delay.mul_f64(*factor).min(*max_delay);
+            }
+            delay
+        };
+        if delay.is_zero() {
+            return Ok(());
+        }
+        let jitter = rand::thread_rng().gen_range(0.0..0.25);
+        std::thread::sleep(delay.mul_f64(1.0 + jitter));
+        Ok(())
+    }
+}
+
+/// OS-level TCP keepalive configuration (`SO_KEEPALIVE` plus the per-socket tuning),
+/// applied in addition to the application-layer `t1`/`t2`/`t3` liveness checks so that
+/// half-open connections get noticed by the kernel even before a supervisory timer fires.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct TcpKeepalive {
+    /// Time a connection must sit idle before the first keepalive probe is sent.
+    pub time: Option<Duration>,
+    /// Interval between subsequent keepalive probes.
+    pub interval: Option<Duration>,
+    /// Number of unacknowledged probes before the connection is considered dead. Only
+    /// honored on Linux, FreeBSD and Windows; ignored on other platforms.
+    pub retries: Option<u32>,
+}
+
+impl TcpKeepalive {
+    fn is_enabled(self) -> bool {
+        self.time.is_some() || self.interval.is_some() || self.retries.is_some()
+    }
+
+    fn to_socket2(self) -> socket2::TcpKeepalive {
+        let mut keepalive = socket2::TcpKeepalive::new();
+        if let Some(time) = self.time {
+            keepalive = keepalive.with_time(time);
+        }
+        if let Some(interval) = self.interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+        if let Some(retries) = self.retries {
+            keepalive = Self::with_retries(keepalive, retries);
+        }
+        keepalive
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "windows"))]
+    fn with_retries(keepalive: socket2::TcpKeepalive, retries: u32) -> socket2::TcpKeepalive {
+        keepalive.with_retries(retries)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "freebsd", target_os = "windows")))]
+    fn with_retries(keepalive: socket2::TcpKeepalive, _retries: u32) -> socket2::TcpKeepalive {
+        // TCP_KEEPCNT is not available on this platform, fall back to time/interval only
+        keepalive
+    }
+}
+
+#[cfg(unix)]
+fn apply_tcp_keepalive(stream: &dyn Stream, keepalive: TcpKeepalive) -> std::io::Result<()> {
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    // the `Socket` is a borrowed view over the fd owned by `stream`, it must never close it
+    let socket = unsafe { socket2::Socket::from_raw_fd(stream.as_raw_fd()) };
+    let result = socket.set_tcp_keepalive(&keepalive.to_socket2());
+    std::mem::forget(socket);
+    result
+}
+
+#[cfg(windows)]
+fn apply_tcp_keepalive(stream: &dyn Stream, keepalive: TcpKeepalive) -> std::io::Result<()> {
+    use std::os::windows::io::{AsRawSocket, FromRawSocket};
+    // the `Socket` is a borrowed view over the socket owned by `stream`, it must never close it
+    let socket = unsafe { socket2::Socket::from_raw_socket(stream.as_raw_socket()) };
+    let result = socket.set_tcp_keepalive(&keepalive.to_socket2());
+    std::mem::forget(socket);
+    result
+}
+
+/// How the reader should react when the telegram channel is full, i.e. a slow consumer isn't
+/// draining frames fast enough.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub enum OverflowPolicy {
+    /// Block the reader loop until the consumer makes room.
+    Block,
+    /// Drop the oldest queued telegram to make room for the incoming one.
+    DropOldest,
+    /// Discard the incoming telegram, leaving the queue as-is.
+    DropNewest,
+    /// Treat a full queue as fatal: tear down the reader loop, forcing a reconnect.
+    #[default]
+    Error,
+}
+
+/// Lifecycle state of an IEC 60870-5 104 connection, readable from `Client`.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub enum ConnectionState {
+    #[default]
+    /// The initial STARTDT handshake has not completed yet.
+    Connecting,
+    /// STARTDT has been confirmed, data transfer is active.
+    Active,
+    /// `Client::stop()` was called (whether or not the STOPDT con arrived); the reader will
+    /// not reconnect automatically.
+    Stopped,
+    /// The connection dropped unexpectedly; a reconnect is in progress.
+    Disconnected,
+    /// The reconnect backoff exhausted its `max_retries`; the client has given up and will
+    /// not reconnect automatically.
+    ReconnectFailed,
+}
+
 /// Ping kind
 #[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
 pub enum PingKind {
     /// Re-connect socket if dropped
     Connect,
     #[default]
-    /// Send test frame (U-frame)
-    Test,
-    /// Send ack frame (S-frame)
-    Ack,
+    /// Check/enforce the t1/t2/t3 supervisory timers and the k/w flow-control window
+    Supervise,
 }
 
 impl fmt::Display for PingKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             PingKind::Connect => write!(f, "socket connect"),
-            PingKind::Test => write!(f, "IEC 60870-5 104 test U-frame"),
-            PingKind::Ack => write!(f, "IEC 60870-5 104 ack S-frame"),
+            PingKind::Supervise => write!(f, "IEC 60870-5 104 supervisory timers"),
         }
     }
 }
@@ -52,17 +318,23 @@ impl Pinger {
     /// Run the pinger worker
     pub fn run(&self) {
         trace!(?self.kind, "pinger started");
-        for _ in interval(self.interval) {
+        'ping: for _ in interval(self.interval) {
             let result = match self.kind {
-                PingKind::Connect => self.inner.client.connect(),
-                PingKind::Test => {
-                    let frame = Telegram104::new_test();
-                    self.inner.send(frame)
-                }
-                PingKind::Ack => {
-                    let frame = Telegram104_S::new();
-                    self.inner.send(frame.into())
-                }
+                PingKind::Connect => match self.inner.client.connect() {
+                    Ok(()) => {
+                        self.inner.reconnect.on_connected();
+                        Ok(())
+                    }
+                    Err(error) => {
+                        if let Err(exhausted) = self.inner.reconnect.wait_before_retry() {
+                            error!(%exhausted, "IEC 60870-5 104 reconnect attempts exhausted, giving up");
+                            self.inner.give_up_reconnecting();
+                            break 'ping;
+                        }
+                        Err(error)
+                    }
+                },
+                PingKind::Supervise => self.inner.supervise(),
             };
             if let Err(error) = result {
                 error!(%error, kind=%self.kind, "remote ping error");
@@ -84,16 +356,24 @@ impl Client {
         addr: A,
         timeouts: Timeouts,
         reader_queue_size: usize,
+        params: Iec104Params,
+        reconnect_strategy: ReconnectStrategy,
+        overflow_policy: OverflowPolicy,
+        keepalive: TcpKeepalive,
     ) -> Result<(Self, Reader)> {
         // make sure lazy is initialized
         assert!(!PUSH_COTS.is_empty());
-        let (inner, reader) = Client104Inner::new(addr, timeouts, reader_queue_size)?;
-        Ok((
-            Self {
-                inner: Arc::new(inner),
-            },
-            reader,
-        ))
+        params.validate()?;
+        let (inner, reader) = Client104Inner::new(
+            addr,
+            timeouts,
+            reader_queue_size,
+            params,
+            reconnect_strategy,
+            overflow_policy,
+            keepalive,
+        )?;
+        Ok((Self { inner }, reader))
     }
     /// Need to be called periodically to accept server pushes if no keep-alive mechanism is
     /// implemented. Does not need to be called if keep-alive is present.
@@ -118,34 +398,280 @@ impl Client {
             interval,
         }
     }
+    /// Gracefully tears down the session: sends a STOPDT act U-frame and waits (bounded by
+    /// `timeouts.write`) for the STOPDT con. Marks the connection as intentionally `Stopped`
+    /// so the `Reader` loop exits afterwards instead of reconnecting.
+    pub fn stop(&self) -> Result<()> {
+        self.inner.stop()
+    }
+    /// Current lifecycle state of the connection.
+    pub fn state(&self) -> ConnectionState {
+        *self.inner.state.lock()
+    }
+    /// Routes the STARTDT handshake through a `test_faults::FaultInjectingStream` configured
+    /// with `config`, so tests can exercise connect/reconnect failures against a simulated
+    /// faulty link. Frames after the handshake use the plain socket. Takes effect on the next
+    /// (re)connect; pass `None` to go back to the plain socket.
+    #[cfg(feature = "test-faults")]
+    pub fn set_fault_config(&self, config: Option<test_faults::FaultConfig>) {
+        *self.inner.connection_handler.fault_config.lock() = config;
+    }
+}
+
+/// Which U-format function a `CommandKey::U` correlates, independent of whether the frame
+/// in hand is the `act` or the `con` half of that function.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum UFunction {
+    StartDt,
+    StopDt,
+    TestFr,
+}
+
+/// Identifies which outstanding `command()` call a given command frame (outgoing) or
+/// response (incoming I-frame/U-frame) belongs to, so independent commands can complete in
+/// parallel without cross-completing each other.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum CommandKey {
+    /// ASDU common address + information object address + type identification.
+    Asdu { ca: u16, type_id: u8, ioa: u32 },
+    /// A U-format frame, keyed by its function so e.g. a TESTFR con can never complete a
+    /// pending STOPDT command.
+    U(UFunction),
+}
+
+impl CommandKey {
+    fn from_frame(frame: &Telegram104) -> Self {
+        match frame {
+            Telegram104::I(i) => CommandKey::Asdu {
+                ca: i.ca(),
+                type_id: i.type_id(),
+                ioa: i.ioa(),
+            },
+            Telegram104::U(u) if u.is_start_dt() => CommandKey::U(UFunction::StartDt),
+            Telegram104::U(u) if u.is_stop_dt() => CommandKey::U(UFunction::StopDt),
+            // The only remaining U-function is TESTFR (act or con).
+            _ => CommandKey::U(UFunction::TestFr),
+        }
+    }
+}
+
+struct PendingCommand {
+    cell: DataCell<Telegram104>,
+    deadline: Instant,
+}
+
+type PendingCommands = Mutex<std::collections::HashMap<CommandKey, PendingCommand>>;
+
+/// Tracks the state needed to enforce the `k`/`w` flow-control window and the `t1`/`t2`/`t3`
+/// supervisory timers on top of the plain chat-sequence validation done by `ChatSequenceCounter`.
+struct FlowControl {
+    params: Iec104Params,
+    state: Mutex<FlowState>,
+}
+
+struct FlowState {
+    /// Our own view of N(S), kept in lock-step with `ChatSequenceCounter` purely so that
+    /// outstanding I-frames can be matched against the N(R) the remote end acknowledges.
+    send_seq: u16,
+    /// `(N(S), sent_at)` of every I-frame sent but not yet acknowledged, oldest first.
+    unacked: VecDeque<(u16, Instant)>,
+    /// Number of I-frames received since we last sent an I- or S-frame (the frames that
+    /// carry N(R)).
+    received_since_ack: u16,
+    /// When the oldest currently-unanswered received I-frame arrived (for `t2`).
+    awaiting_ack_since: Option<Instant>,
+    /// When the most recently sent TESTFR act went out, until its con is received (for `t1`,
+    /// which covers "an outstanding I- or TESTFR frame").
+    outstanding_test_fr: Option<Instant>,
+    last_sent: Instant,
+    last_received: Instant,
 }
 
-type CommandResponseTx = Arc<Mutex<Option<DataCell<Telegram104>>>>;
+impl FlowState {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            send_seq: 0,
+            unacked: VecDeque::new(),
+            received_since_ack: 0,
+            awaiting_ack_since: None,
+            outstanding_test_fr: None,
+            last_sent: now,
+            last_received: now,
+        }
+    }
+}
+
+impl FlowControl {
+    fn new(params: Iec104Params) -> Self {
+        Self {
+            params,
+            state: Mutex::new(FlowState::new()),
+        }
+    }
+
+    fn reset(&self) {
+        *self.state.lock() = FlowState::new();
+    }
+
+    /// Blocks until the `k`-sized send window has room for another I-format APDU.
+    fn wait_for_window(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.state.lock().unacked.len() < usize::from(self.params.k) {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(roboplc::Error::timeout());
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn register_i_sent(&self) {
+        let mut state = self.state.lock();
+        let seq = state.send_seq;
+        // N(S) is a 15-bit field (wraps at 32768), unlike the plain u16 it's stored in here.
+        state.send_seq = state.send_seq.wrapping_add(1) & 0x7fff;
+        state.unacked.push_back((seq, Instant::now()));
+        state.received_since_ack = 0;
+        state.awaiting_ack_since = None;
+    }
+
+    /// Called after an S-frame has been successfully written: it carries N(R), so it
+    /// satisfies `t2` and resets the `w` receive counter just like an I-frame does.
+    fn register_s_sent(&self) {
+        let mut state = self.state.lock();
+        state.received_since_ack = 0;
+        state.awaiting_ack_since = None;
+    }
+
+    /// Called after any frame (I, S or U) has been successfully written, to satisfy `t3`.
+    /// U-frames (e.g. TESTFR act) carry no N(R), so they must not clear the `w`/`t2` state.
+    fn register_sent(&self) {
+        self.state.lock().last_sent = Instant::now();
+    }
+
+    /// Called after a TESTFR act has been successfully written, so `t1` can cover it exactly
+    /// like an outstanding I-frame until the TESTFR con arrives.
+    fn register_test_fr_sent(&self) {
+        self.state.lock().outstanding_test_fr = Some(Instant::now());
+    }
+
+    /// Called for every received I- or S-frame, which carry N(R) and cumulatively
+    /// acknowledge all outstanding I-frames up to (but excluding) that sequence number.
+    /// A `nr` that doesn't match any outstanding sequence (a stale, backwards or corrupt
+    /// N(R)) is ignored rather than treated as "ack everything".
+    fn register_ack(&self, nr: u16) {
+        let mut state = self.state.lock();
+        state.last_received = Instant::now();
+        let is_outstanding =
+            nr == state.send_seq || state.unacked.iter().any(|&(seq, _)| seq == nr);
+        if !is_outstanding {
+            return;
+        }
+        while let Some(&(seq, _)) = state.unacked.front() {
+            if seq == nr {
+                break;
+            }
+            state.unacked.pop_front();
+        }
+    }
+
+    /// Called for every received I-frame. Returns `true` once `w` frames have accumulated
+    /// and an S-frame acknowledgement must be sent immediately.
+    fn register_i_received(&self) -> bool {
+        let mut state = self.state.lock();
+        state.last_received = Instant::now();
+        if state.awaiting_ack_since.is_none() {
+            state.awaiting_ack_since = Some(state.last_received);
+        }
+        state.received_since_ack += 1;
+        state.received_since_ack >= self.params.w
+    }
+
+    /// Called for every received U-frame; a TESTFR con (`test_fr_con`) also clears the
+    /// outstanding TESTFR deadline.
+    fn register_received(&self, test_fr_con: bool) {
+        let mut state = self.state.lock();
+        state.last_received = Instant::now();
+        if test_fr_con {
+            state.outstanding_test_fr = None;
+        }
+    }
+
+    /// Returns `(t1 expired, t2 expired, t3 expired)` relative to now.
+    fn check_timers(&self) -> (bool, bool, bool) {
+        let now = Instant::now();
+        let state = self.state.lock();
+        let t1 = state
+            .unacked
+            .front()
+            .is_some_and(|&(_, sent_at)| now.duration_since(sent_at) > self.params.t1)
+            || state
+                .outstanding_test_fr
+                .is_some_and(|sent_at| now.duration_since(sent_at) > self.params.t1);
+        let t2 = state
+            .awaiting_ack_since
+            .is_some_and(|since| now.duration_since(since) > self.params.t2);
+        let t3 = now.duration_since(state.last_sent.max(state.last_received)) > self.params.t3;
+        (t1, t2, t3)
+    }
+}
 
 struct Client104Inner {
     client: roboplc::comm::Client,
     connection_handler: IecConnectionHandler,
     timeouts: Timeouts,
-    command_response_tx: CommandResponseTx,
-    command_lock: Mutex<()>,
+    pending_commands: PendingCommands,
+    flow: FlowControl,
+    reconnect: Arc<ReconnectHandler>,
+    state: Arc<Mutex<ConnectionState>>,
+    stopping: Arc<AtomicBool>,
 }
 
 #[derive(Clone, Default)]
 struct IecConnectionHandler {
     chat_seq: ChatSequenceCounter,
     chat_seq_lock: Arc<Mutex<()>>,
+    reconnect: Arc<OptionReconnectHandler>,
+    keepalive: TcpKeepalive,
+    state: Arc<Mutex<ConnectionState>>,
+    /// Set via `Client::set_fault_config` to run the STARTDT handshake through a
+    /// `test_faults::FaultInjectingStream`. Frames after the handshake are not affected.
+    #[cfg(feature = "test-faults")]
+    fault_config: Arc<Mutex<Option<test_faults::FaultConfig>>>,
 }
 
-impl ConnectionHandler for IecConnectionHandler {
-    fn on_connect(
+/// Lazily-populated handle to the client's `ReconnectHandler`, needed because
+/// `IecConnectionHandler` is constructed before `Client104Inner` (and its `Arc`) exists.
+#[derive(Default)]
+struct OptionReconnectHandler(Mutex<Option<Arc<ReconnectHandler>>>);
+
+impl OptionReconnectHandler {
+    fn set(&self, handler: Arc<ReconnectHandler>) {
+        *self.0.lock() = Some(handler);
+    }
+
+    fn on_connected(&self) {
+        if let Some(handler) = self.0.lock().as_ref() {
+            handler.on_connected();
+        }
+    }
+}
+
+impl IecConnectionHandler {
+    /// Sends the STARTDT act and validates its confirmation over whatever `Read + Write`
+    /// transport it is given, so the real socket and a `test_faults::FaultInjectingStream`
+    /// wrapping it share the exact same handshake logic.
+    fn start_dt_handshake(
         &self,
-        stream: &mut dyn Stream,
+        rw: &mut (impl std::io::Read + std::io::Write),
     ) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.chat_seq.reset();
         let mut req = Cursor::new(Vec::new());
         Telegram104::new_start_dt().write(&mut req)?;
-        stream.write_all(&req.into_inner())?;
-        let reply = Telegram104::read(stream)?;
+        rw.write_all(&req.into_inner())?;
+        let reply = Telegram104::read(rw)?;
         let Telegram104::U(r) = reply else {
             return Err("unexpected reply".into());
         };
@@ -156,13 +682,52 @@ impl ConnectionHandler for IecConnectionHandler {
     }
 }
 
+impl ConnectionHandler for IecConnectionHandler {
+    fn on_connect(
+        &self,
+        stream: &mut dyn Stream,
+    ) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.chat_seq.reset();
+        if self.keepalive.is_enabled() {
+            if let Err(error) = apply_tcp_keepalive(stream, self.keepalive) {
+                warn!(%error, "failed to apply TCP keepalive settings");
+            }
+        }
+        #[cfg(feature = "test-faults")]
+        {
+            if let Some(config) = self.fault_config.lock().clone() {
+                let mut faulty = test_faults::FaultInjectingStream::new(stream, config);
+                self.start_dt_handshake(&mut faulty)?;
+                self.reconnect.on_connected();
+                *self.state.lock() = ConnectionState::Active;
+                return Ok(());
+            }
+        }
+        self.start_dt_handshake(stream)?;
+        self.reconnect.on_connected();
+        *self.state.lock() = ConnectionState::Active;
+        Ok(())
+    }
+}
+
 impl Client104Inner {
     pub fn new<A: ToSocketAddrs + fmt::Debug>(
         addr: A,
         timeouts: Timeouts,
         reader_queue_size: usize,
-    ) -> Result<(Self, Reader)> {
-        let connection_handler = IecConnectionHandler::default();
+        params: Iec104Params,
+        reconnect_strategy: ReconnectStrategy,
+        overflow_policy: OverflowPolicy,
+        keepalive: TcpKeepalive,
+    ) -> Result<(Arc<Self>, Reader)> {
+        let state = Arc::new(Mutex::new(ConnectionState::Connecting));
+        let connection_handler = IecConnectionHandler {
+            keepalive,
+            state: state.clone(),
+            ..IecConnectionHandler::default()
+        };
+        let reconnect = Arc::new(ReconnectHandler::new(reconnect_strategy));
+        connection_handler.reconnect.set(reconnect.clone());
 
         let (client, reader_rx) = roboplc::comm::tcp::connect_with_options(
             addr,
@@ -175,32 +740,41 @@ impl Client104Inner {
         let (restart_tx, restart_rx) = policy_channel::bounded(1);
         let (telegram_tx, telegram_rx) = rtsc::channel::bounded(reader_queue_size);
 
-        let command_response_tx: CommandResponseTx = <_>::default();
+        let inner = Arc::new(Self {
+            client: client.clone(),
+            connection_handler: connection_handler.clone(),
+            timeouts,
+            pending_commands: Mutex::new(std::collections::HashMap::new()),
+            flow: FlowControl::new(params),
+            reconnect,
+            state,
+            stopping: Arc::new(AtomicBool::new(false)),
+        });
 
         let reader = Reader {
-            client: client.clone(),
+            client,
             reader_rx,
             restart_rx,
             restart_tx,
             telegram_rx,
             telegram_tx,
-            command_response_tx: command_response_tx.clone(),
-            connection_handler: connection_handler.clone(),
+            connection_handler,
+            inner: inner.clone(),
+            overflow_policy,
+            telegram_drops: Arc::new(AtomicU64::new(0)),
         };
 
-        Ok((
-            Self {
-                client,
-                connection_handler,
-                timeouts,
-                command_response_tx,
-                command_lock: Mutex::new(()),
-            },
-            reader,
-        ))
+        Ok((inner, reader))
     }
 
     pub fn send(&self, mut frame: Telegram104) -> Result<()> {
+        // Wait for window room *before* taking `chat_seq_lock`: the window only drains once
+        // `run_inner` processes the peer's N(R), and it takes the very same lock to do so
+        // (to validate/apply the chat sequence). Holding `chat_seq_lock` across this wait
+        // would deadlock the reader out of ever delivering that ack.
+        if matches!(frame, Telegram104::I(_)) {
+            self.flow.wait_for_window(self.timeouts.write)?;
+        }
         let _chat_seq_lock = self
             .connection_handler
             .chat_seq_lock
@@ -215,20 +789,93 @@ impl Client104Inner {
         frame.chat_sequence_apply_outgoing(&self.connection_handler.chat_seq);
         frame.write(&mut req).map_err(roboplc::Error::io)?;
         self.client.write(&req.into_inner())?;
+        match &frame {
+            Telegram104::I(_) => self.flow.register_i_sent(),
+            Telegram104::S(_) => self.flow.register_s_sent(),
+            Telegram104::U(u) if u.is_test() => self.flow.register_test_fr_sent(),
+            Telegram104::U(_) => {}
+        }
+        self.flow.register_sent();
         Ok(())
     }
 
     pub fn command(&self, frame: Telegram104) -> Result<Telegram104> {
-        let _lock = self.command_lock.try_lock_for(self.timeouts.write);
+        let key = CommandKey::from_frame(&frame);
         let cell = DataCell::new();
-        self.command_response_tx.lock().replace(cell.clone());
+        let deadline = Instant::now() + self.timeouts.write;
+        {
+            let mut pending = self.pending_commands.lock();
+            // opportunistically evict stale entries left behind by expired commands
+            pending.retain(|_, p| p.deadline > Instant::now());
+            pending.insert(
+                key,
+                PendingCommand {
+                    cell: cell.clone(),
+                    deadline,
+                },
+            );
+        }
         if let Err(e) = self.send(frame) {
-            if let Some(d) = self.command_response_tx.lock().take() {
-                d.close();
+            if let Some(pending) = self.pending_commands.lock().remove(&key) {
+                pending.cell.close();
             }
             return Err(e);
         }
-        cell.get_timeout(self.timeouts.write).map_err(Into::into)
+        let result = cell.get_timeout(self.timeouts.write).map_err(Into::into);
+        self.pending_commands.lock().remove(&key);
+        result
+    }
+
+    /// Gracefully tears down the session: sends a STOPDT act U-frame and waits for the
+    /// STOPDT con (reusing the same pending-command dispatch as `command()`), then marks the
+    /// connection `Stopped` so the reader does not reconnect afterwards.
+    fn stop(&self) -> Result<()> {
+        self.stopping.store(true, Ordering::SeqCst);
+        let result = self.command(Telegram104::new_stop_dt());
+        *self.state.lock() = ConnectionState::Stopped;
+        match result {
+            Ok(Telegram104::U(u)) if u.is_stop_dt() && u.is_con() => Ok(()),
+            Ok(_) => Err(roboplc::Error::invalid_data(
+                "unexpected reply to STOPDT act",
+            )),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Marks the connection as given up on: the reconnect backoff exhausted its
+    /// `max_retries`. Has the same effect on the reader loop as `stop()` (no further
+    /// reconnect attempts), but reflects that this was not a user-requested stop.
+    fn give_up_reconnecting(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        *self.state.lock() = ConnectionState::ReconnectFailed;
+    }
+
+    /// Enforces the `t1`/`t2`/`t3` supervisory timers. Meant to be called periodically
+    /// (faster than the smallest configured timer) by a `PingKind::Supervise` pinger.
+    fn supervise(&self) -> Result<()> {
+        if self.stopping.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let (t1_expired, t2_expired, t3_expired) = self.flow.check_timers();
+        if t1_expired {
+            error!("IEC 60870-5 104 t1 timeout, no acknowledgement received in time");
+            self.flow.reset();
+            if let Err(error) = self.reconnect.wait_before_retry() {
+                error!(%error, "IEC 60870-5 104 reconnect attempts exhausted, giving up");
+                self.give_up_reconnecting();
+                return Ok(());
+            }
+            self.client.reconnect();
+            return Ok(());
+        }
+        if t2_expired {
+            debug!("IEC 60870-5 104 t2 expired, sending spontaneous S-frame acknowledgement");
+            self.send(Telegram104_S::new().into())?;
+        } else if t3_expired {
+            trace!("IEC 60870-5 104 t3 idle timeout, sending TESTFR act");
+            self.send(Telegram104::new_test())?;
+        }
+        Ok(())
     }
 }
 
@@ -250,8 +897,10 @@ pub struct Reader {
     restart_tx: Sender<RestartEvent>,
     telegram_rx: roboplc::channel::Receiver<Telegram104>,
     telegram_tx: roboplc::channel::Sender<Telegram104>,
-    command_response_tx: CommandResponseTx,
     connection_handler: IecConnectionHandler,
+    inner: Arc<Client104Inner>,
+    overflow_policy: OverflowPolicy,
+    telegram_drops: Arc<AtomicU64>,
 }
 
 impl Reader {
@@ -267,6 +916,7 @@ impl Reader {
             self.restart_tx
                 .send(RestartEvent {})
                 .expect("never disconnects");
+            self.inner.flow.reset();
             if first_start {
                 first_start = false;
             } else {
@@ -274,9 +924,19 @@ impl Reader {
             }
             trace!(session_id, "spawning reader");
             self.run_inner(reader);
+            if self.inner.stopping.load(Ordering::SeqCst) {
+                debug!("IEC 60870-5 104 reader stopped intentionally, not reconnecting");
+                break;
+            }
+            *self.inner.state.lock() = ConnectionState::Disconnected;
             // reconnect the client in case it has not been done yet
             if session_id == self.client.session_id() {
                 debug!("reader asked the client to reconnect");
+                if let Err(error) = self.inner.reconnect.wait_before_retry() {
+                    error!(%error, "IEC 60870-5 104 reconnect attempts exhausted, giving up");
+                    self.inner.give_up_reconnecting();
+                    break;
+                }
                 self.client.reconnect();
             }
         }
@@ -301,6 +961,48 @@ impl Reader {
         self.telegram_rx.clone()
     }
 
+    /// Number of telegrams dropped so far because of the configured `OverflowPolicy`
+    /// (always zero under `OverflowPolicy::Block` and `OverflowPolicy::Error`).
+    pub fn dropped_telegrams(&self) -> u64 {
+        self.telegram_drops.load(Ordering::Relaxed)
+    }
+
+    /// Hands a received telegram to `telegram_tx`, honoring `overflow_policy` when the
+    /// (bounded) channel is full. Returns `false` only when the reader loop must stop.
+    fn send_telegram(&self, mut telegram: Telegram104) -> bool {
+        loop {
+            match self.overflow_policy {
+                OverflowPolicy::Error => return self.telegram_tx.send(telegram).is_ok(),
+                OverflowPolicy::DropNewest => {
+                    if self.telegram_tx.send(telegram).is_err() {
+                        self.telegram_drops.fetch_add(1, Ordering::Relaxed);
+                        warn!("IEC 60870-5 104 telegram queue full, dropping incoming telegram");
+                    }
+                    return true;
+                }
+                OverflowPolicy::DropOldest => match self.telegram_tx.send(telegram) {
+                    Ok(()) => return true,
+                    Err(e) => {
+                        telegram = e.0;
+                        if self.telegram_rx.try_recv().is_err() {
+                            // the consumer drained the queue in the meantime, retry the send
+                            continue;
+                        }
+                        self.telegram_drops.fetch_add(1, Ordering::Relaxed);
+                        warn!("IEC 60870-5 104 telegram queue full, dropping oldest telegram");
+                    }
+                },
+                OverflowPolicy::Block => match self.telegram_tx.send(telegram) {
+                    Ok(()) => return true,
+                    Err(e) => {
+                        telegram = e.0;
+                        std::thread::sleep(Duration::from_millis(5));
+                    }
+                },
+            }
+        }
+    }
+
     fn run_inner(&self, mut reader: CommReader) {
         let mut socket = reader.take().expect("can not get reader socket");
         loop {
@@ -320,20 +1022,267 @@ impl Reader {
                     break;
                 }
             }
+            match &telegram {
+                Telegram104::I(i) => self.inner.flow.register_ack(i.nr()),
+                Telegram104::S(s) => self.inner.flow.register_ack(s.nr()),
+                Telegram104::U(u) => self.inner.flow.register_received(u.is_test() && u.is_con()),
+            }
             if let Telegram104::I(ref i) = telegram {
+                if self.inner.flow.register_i_received() {
+                    if let Err(error) = self.inner.send(Telegram104_S::new().into()) {
+                        error!(%error, "failed to send IEC 60870-5 104 w-window acknowledgement");
+                    }
+                }
                 if !PUSH_COTS.contains(&i.cot()) {
-                    if let Some(ref command_response_tx) = self.command_response_tx.lock().take() {
-                        if !command_response_tx.is_closed() {
-                            command_response_tx.set(telegram);
+                    let key = CommandKey::from_frame(&telegram);
+                    if let Some(pending) = self.inner.pending_commands.lock().remove(&key) {
+                        if !pending.cell.is_closed() {
+                            pending.cell.set(telegram);
                         }
                         continue;
                     }
                 }
             }
-            if self.telegram_tx.send(telegram).is_err() {
+            if let Telegram104::U(_) = telegram {
+                // U-frame confirmations (e.g. STOPDT con) are dispatched the same way as
+                // command responses, keyed by their U-function so a TESTFR con can't
+                // complete a pending STOPDT (or vice versa).
+                let key = CommandKey::from_frame(&telegram);
+                if let Some(pending) = self.inner.pending_commands.lock().remove(&key) {
+                    if !pending.cell.is_closed() {
+                        pending.cell.set(telegram);
+                    }
+                    continue;
+                }
+            }
+            if !self.send_telegram(telegram) {
                 error!("IEC 60870-5 104 reader telegram_tx failed");
                 break;
             }
         }
     }
 }
+
+/// Deterministic network fault injection (packet loss, latency, reordering and mid-stream
+/// disconnects) for tests. `Client::set_fault_config` routes only the STARTDT handshake
+/// through a `FaultInjectingStream`; frames read by the `Reader` and written by
+/// `Client::send`/`Client::command` use the plain socket.
+#[cfg(feature = "test-faults")]
+pub mod test_faults {
+    use super::Stream;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::{
+        collections::VecDeque,
+        io::{Read, Result as IoResult, Write},
+        time::Duration,
+    };
+
+    /// Fault parameters for `FaultInjectingStream`. All probabilities are in `0.0..=1.0`.
+    #[derive(Clone, Debug)]
+    pub struct FaultConfig {
+        /// Extra latency applied before every read.
+        pub latency: Duration,
+        /// Probability that an outgoing write is silently swallowed (never reaches the peer).
+        pub drop_probability: f64,
+        /// Probability that an outgoing write is put on the wire twice.
+        pub duplicate_probability: f64,
+        /// Number of trailing writes held back and emitted in shuffled order once the window
+        /// fills up. `0` disables reordering.
+        pub reorder_window: usize,
+        /// Once this many bytes have been written, all further reads/writes fail with
+        /// `ErrorKind::UnexpectedEof` as if the peer had vanished mid-stream.
+        pub disconnect_after_bytes: Option<usize>,
+        /// Seed driving the PRNG behind all of the above, for reproducible test runs.
+        pub seed: u64,
+    }
+
+    impl Default for FaultConfig {
+        fn default() -> Self {
+            Self {
+                latency: Duration::ZERO,
+                drop_probability: 0.0,
+                duplicate_probability: 0.0,
+                reorder_window: 0,
+                disconnect_after_bytes: None,
+                seed: 0,
+            }
+        }
+    }
+
+    /// Wraps a `Stream` and injects faults on top of it according to a `FaultConfig`.
+    pub struct FaultInjectingStream<S> {
+        inner: S,
+        config: FaultConfig,
+        rng: StdRng,
+        bytes_written: usize,
+        disconnected: bool,
+        pending_writes: VecDeque<Vec<u8>>,
+    }
+
+    impl<S: Read + Write> FaultInjectingStream<S> {
+        /// Wraps `inner`, injecting faults described by `config`.
+        pub fn new(inner: S, config: FaultConfig) -> Self {
+            let rng = StdRng::seed_from_u64(config.seed);
+            Self {
+                inner,
+                config,
+                rng,
+                bytes_written: 0,
+                disconnected: false,
+                pending_writes: VecDeque::new(),
+            }
+        }
+
+        fn check_disconnected(&mut self) -> IoResult<()> {
+            if !self.disconnected {
+                if let Some(limit) = self.config.disconnect_after_bytes {
+                    self.disconnected = self.bytes_written >= limit;
+                }
+            }
+            if self.disconnected {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "fault-injected disconnect",
+                ));
+            }
+            Ok(())
+        }
+
+        /// Shuffles and flushes the reorder window to the underlying stream.
+        fn flush_reordered(&mut self) -> IoResult<()> {
+            let mut frames: Vec<_> = self.pending_writes.drain(..).collect();
+            for i in (1..frames.len()).rev() {
+                let j = self.rng.gen_range(0..=i);
+                frames.swap(i, j);
+            }
+            for frame in frames {
+                self.inner.write_all(&frame)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl<S: Read + Write> Read for FaultInjectingStream<S> {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+            self.check_disconnected()?;
+            if !self.config.latency.is_zero() {
+                std::thread::sleep(self.config.latency);
+            }
+            self.inner.read(buf)
+        }
+    }
+
+    impl<S: Read + Write> Write for FaultInjectingStream<S> {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            self.check_disconnected()?;
+            self.bytes_written += buf.len();
+            if self
+                .rng
+                .gen_bool(self.config.drop_probability.clamp(0.0, 1.0))
+            {
+                // pretend the write succeeded, but never actually send the bytes
+                return Ok(buf.len());
+            }
+            if self.config.reorder_window > 0 {
+                self.pending_writes.push_back(buf.to_vec());
+                if self.pending_writes.len() >= self.config.reorder_window {
+                    self.flush_reordered()?;
+                }
+            } else {
+                self.inner.write_all(buf)?;
+            }
+            if self
+                .rng
+                .gen_bool(self.config.duplicate_probability.clamp(0.0, 1.0))
+            {
+                self.inner.write_all(buf)?;
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            self.flush_reordered()?;
+            self.inner.flush()
+        }
+    }
+
+    impl<S: Stream> Stream for FaultInjectingStream<S> {}
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::io::Cursor;
+
+        fn config(seed: u64) -> FaultConfig {
+            FaultConfig {
+                seed,
+                ..FaultConfig::default()
+            }
+        }
+
+        #[test]
+        fn drop_probability_swallows_writes() {
+            let mut stream = FaultInjectingStream::new(
+                Cursor::new(Vec::new()),
+                FaultConfig {
+                    drop_probability: 1.0,
+                    ..config(1)
+                },
+            );
+            assert_eq!(stream.write(b"startdt act").unwrap(), 11);
+            assert!(stream.inner.get_ref().is_empty());
+        }
+
+        #[test]
+        fn duplicate_probability_sends_writes_twice() {
+            let mut stream = FaultInjectingStream::new(
+                Cursor::new(Vec::new()),
+                FaultConfig {
+                    duplicate_probability: 1.0,
+                    ..config(2)
+                },
+            );
+            stream.write_all(b"testfr").unwrap();
+            assert_eq!(stream.inner.get_ref(), b"testfrtestfr");
+        }
+
+        #[test]
+        fn disconnect_after_bytes_fails_subsequent_io() {
+            let mut stream = FaultInjectingStream::new(
+                Cursor::new(Vec::new()),
+                FaultConfig {
+                    disconnect_after_bytes: Some(4),
+                    ..config(3)
+                },
+            );
+            stream.write_all(b"abcd").unwrap();
+            let error = stream
+                .write_all(b"e")
+                .expect_err("write after the byte limit must fail");
+            assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+            let mut buf = [0u8; 1];
+            let error = stream
+                .read(&mut buf)
+                .expect_err("read after the byte limit must fail too");
+            assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        }
+
+        #[test]
+        fn reorder_window_eventually_emits_every_frame() {
+            let mut stream = FaultInjectingStream::new(
+                Cursor::new(Vec::new()),
+                FaultConfig {
+                    reorder_window: 3,
+                    ..config(4)
+                },
+            );
+            stream.write_all(b"a").unwrap();
+            stream.write_all(b"b").unwrap();
+            assert!(stream.inner.get_ref().is_empty(), "window not full yet");
+            stream.write_all(b"c").unwrap();
+            let mut written: Vec<u8> = stream.inner.get_ref().clone();
+            written.sort_unstable();
+            assert_eq!(written, b"abc");
+        }
+    }
+}